@@ -0,0 +1,297 @@
+use crate::{NPE, TreeItem};
+
+/// A plain binary indexed tree over `0..len`, 1-indexed internally.
+#[derive(Clone)]
+struct Fenwick {
+    tree: Vec<i64>,
+    len: usize,
+}
+
+impl Fenwick {
+    fn new(len: usize) -> Self {
+        Self {
+            tree: vec![0; len + 1],
+            len,
+        }
+    }
+
+    fn add(&mut self, pos: usize, delta: i64) {
+        let mut i = pos + 1;
+        while i <= self.len {
+            self.tree[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    fn set(&mut self, pos: usize, bit: bool) {
+        let delta = if bit { 1 } else { -1 };
+        self.add(pos, delta);
+    }
+
+    /// Sum over `0..=pos`.
+    fn prefix_sum(&self, pos: usize) -> i64 {
+        let mut i = pos + 1;
+        let mut sum = 0;
+        while i > 0 {
+            sum += self.tree[i];
+            i -= i & i.wrapping_neg();
+        }
+        sum
+    }
+
+    fn get(&self, pos: usize) -> bool {
+        let before = match pos.checked_sub(1) {
+            Some(p) => self.prefix_sum(p),
+            None => 0,
+        };
+        self.prefix_sum(pos) - before > 0
+    }
+
+    /// Index of the k-th set bit (1-indexed `k`), via binary lifting over
+    /// the Fenwick tree in O(log n).
+    fn find_kth(&self, k: i64) -> usize {
+        let mut pos = 0;
+        let mut remaining = k;
+        let mut log = 1;
+        while (log << 1) <= self.len {
+            log <<= 1;
+        }
+
+        let mut step = log;
+        while step > 0 {
+            let next = pos + step;
+            if next <= self.len && self.tree[next] < remaining {
+                pos = next;
+                remaining -= self.tree[next];
+            }
+            step >>= 1;
+        }
+
+        pos
+    }
+}
+
+/// Order-statistic companion to an [`NPE`]: makes the a-th operand, the
+/// n-th cut chain, and the ballot prefix counts all O(log n) lookups, so
+/// `m1`/`m2` pick their operand/chain without scanning `expr`, and `m3`'s
+/// ballot check (the part that used to force a full `calculate_ballot`
+/// rescan on every accepted swap) no longer does either. `m3` still scans
+/// `expr` once per call to find its candidate windows — picking one of
+/// those uniformly at random in O(log n) would need its own Fenwick over
+/// boundary positions, which this companion doesn't maintain. Small
+/// problems can skip this and keep using the plain `NPE` methods directly.
+///
+/// Both bitsets carry one extra sentinel position at `len` (always marked
+/// as an operand) so the trailing cut chain after the last real operand —
+/// which `NPE::chains` yields once its main loop ends — is just another
+/// chain ending at the sentinel instead of a special case.
+#[derive(Clone)]
+pub struct NpeIndex {
+    operand_bit: Fenwick,
+    chain_end_bit: Fenwick,
+    len: usize,
+}
+
+impl NpeIndex {
+    pub fn new(npe: &NPE) -> Self {
+        let len = npe.expr.len();
+        let mut operand_bit = Fenwick::new(len + 1);
+        let mut chain_end_bit = Fenwick::new(len + 1);
+
+        let mut prev_operand = None;
+        for (i, item) in npe.expr.iter().enumerate() {
+            if item.is_rect() {
+                operand_bit.set(i, true);
+                if let Some(p) = prev_operand {
+                    if i - p > 1 {
+                        chain_end_bit.set(i, true);
+                    }
+                }
+                prev_operand = Some(i);
+            }
+        }
+
+        operand_bit.set(len, true);
+        if let Some(p) = prev_operand {
+            if len - p > 1 {
+                chain_end_bit.set(len, true);
+            }
+        }
+
+        Self {
+            operand_bit,
+            chain_end_bit,
+            len,
+        }
+    }
+
+    /// The position of the a-th operand (0-indexed) in O(log n).
+    pub fn nth_operand(&self, a: usize) -> usize {
+        self.operand_bit.find_kth(a as i64 + 1)
+    }
+
+    /// The `expr[a..b]` bounds of the n-th non-empty cut chain, matching
+    /// [`NPE::chains`](crate::NPE::chains).
+    pub fn nth_chain(&self, n: usize) -> (usize, usize) {
+        let b = self.chain_end_bit.find_kth(n as i64 + 1);
+        let rank = self.operand_bit.prefix_sum(b);
+        let prev_operand = self.operand_bit.find_kth(rank - 1);
+
+        (prev_operand + 1, b)
+    }
+
+    /// Operand/operator counts over `expr[0..=i]`, mirroring `NPE::ballot`.
+    pub fn ballot(&self, i: usize) -> (usize, usize) {
+        let operands = self.operand_bit.prefix_sum(i) as usize;
+        (operands, i + 1 - operands)
+    }
+
+    /// The number of non-empty cut chains, matching `NPE::chains().count()`.
+    pub fn num_chains(&self) -> usize {
+        self.chain_end_bit.prefix_sum(self.len) as usize
+    }
+
+    // `pos == self.len` recomputes the trailing chain at the sentinel;
+    // otherwise `pos` must be a real operand position.
+    fn recompute_chain_end(&mut self, expr: &[TreeItem], pos: usize) {
+        let is_operand = pos == self.len || expr[pos].is_rect();
+
+        let is_end = if is_operand {
+            let rank = self.operand_bit.prefix_sum(pos);
+            if rank > 1 {
+                let prev = self.operand_bit.find_kth(rank - 1);
+                pos - prev > 1
+            } else {
+                false
+            }
+        } else {
+            false
+        };
+
+        if is_end != self.chain_end_bit.get(pos) {
+            self.chain_end_bit.set(pos, is_end);
+        }
+    }
+
+    /// Call after `NPE::m3` swaps the adjacent pair `expr[i], expr[i + 1]`
+    /// (the only move that changes which positions hold operands vs cuts).
+    pub fn on_adjacent_swap(&mut self, expr: &[TreeItem], i: usize) {
+        for &p in &[i, i + 1] {
+            let now_rect = expr[p].is_rect();
+            if now_rect != self.operand_bit.get(p) {
+                self.operand_bit.set(p, now_rect);
+            }
+        }
+
+        // Recheck chain-end status at both touched positions (clearing it
+        // if a position stopped being an operand) and at the next real
+        // operand after them, whose distance to its predecessor may have
+        // shifted.
+        for &p in &[i, i + 1] {
+            self.recompute_chain_end(expr, p);
+        }
+
+        let total = self.operand_bit.prefix_sum(self.len - 1);
+        let rank_after = self.operand_bit.prefix_sum(i + 1);
+        if rank_after < total {
+            let next = self.operand_bit.find_kth(rank_after + 1);
+            self.recompute_chain_end(expr, next);
+        } else {
+            // The swap touched the last real operand (or nothing follows
+            // it), so the trailing chain's start may have shifted too.
+            self.recompute_chain_end(expr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cut, NPE};
+
+    fn sample() -> Vec<TreeItem> {
+        vec![
+            TreeItem::Rect(0),
+            TreeItem::Rect(1),
+            TreeItem::Cut(Cut::Vertical),
+            TreeItem::Rect(2),
+            TreeItem::Cut(Cut::Horizontal),
+            TreeItem::Cut(Cut::Vertical),
+            TreeItem::Rect(3),
+            TreeItem::Cut(Cut::Horizontal),
+        ]
+    }
+
+    #[test]
+    fn nth_operand_matches_linear_scan() {
+        let expr = sample();
+        let npe = NPE::new(expr.clone());
+        let index = npe.index();
+
+        let operands: Vec<usize> = expr
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| if item.is_rect() { Some(i) } else { None })
+            .collect();
+
+        for (a, &pos) in operands.iter().enumerate() {
+            assert_eq!(index.nth_operand(a), pos);
+        }
+    }
+
+    #[test]
+    fn nth_chain_matches_chains_iterator() {
+        let expr = sample();
+        let npe = NPE::new(expr);
+        let index = npe.index();
+
+        let chains: Vec<(usize, usize)> = npe.chains().collect();
+        assert_eq!(index.num_chains(), chains.len());
+
+        for (n, &expected) in chains.iter().enumerate() {
+            assert_eq!(index.nth_chain(n), expected);
+        }
+    }
+
+    #[test]
+    fn trailing_chain_after_last_operand_is_found() {
+        // No operand after the last real one: the sentinel should surface
+        // that trailing run of cuts as its own chain, matching `chains()`.
+        let expr = vec![
+            TreeItem::Rect(0),
+            TreeItem::Cut(Cut::Vertical),
+            TreeItem::Rect(1),
+            TreeItem::Cut(Cut::Vertical),
+            TreeItem::Cut(Cut::Horizontal),
+        ];
+        let npe = NPE::new(expr);
+        let index = npe.index();
+
+        let chains: Vec<(usize, usize)> = npe.chains().collect();
+        assert_eq!(chains, vec![(1, 2), (3, 5)]);
+        assert_eq!(index.num_chains(), 2);
+        assert_eq!(index.nth_chain(1), (3, 5));
+    }
+
+    #[test]
+    fn on_adjacent_swap_matches_fresh_rebuild() {
+        let mut expr = sample();
+        let mut npe = NPE::new(expr.clone());
+        let mut index = npe.index();
+
+        // `expr[1], expr[2]` is the rect/cut pair `on_adjacent_swap` is for.
+        npe.swap(1, 2);
+        expr.swap(1, 2);
+        index.on_adjacent_swap(&expr, 1);
+
+        let fresh = NPE::new(expr).index();
+
+        for a in 0..npe.count_operands() {
+            assert_eq!(index.nth_operand(a), fresh.nth_operand(a));
+        }
+        assert_eq!(index.num_chains(), fresh.num_chains());
+        for n in 0..fresh.num_chains() {
+            assert_eq!(index.nth_chain(n), fresh.nth_chain(n));
+        }
+    }
+}
@@ -1,5 +1,15 @@
 use std::{fmt, hint::unreachable_unchecked};
 
+mod index;
+mod pack;
+mod seg;
+mod shape;
+
+pub use index::NpeIndex;
+pub use pack::{combined_cost, hpwl, Net, Packing};
+pub use seg::NpeSegTree;
+pub use shape::{rotations, Choice, ShapeTree};
+
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Rect {
     width: usize,
@@ -15,6 +25,14 @@ impl Rect {
         std::mem::swap(&mut self.width, &mut self.height);
     }
 
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
     pub fn cut(self, cut: Cut) -> (Self, Self) {
         match cut {
             Cut::Horizontal => {
@@ -200,6 +218,51 @@ impl SlicingTree {
         tree
     }
 
+    /// Builds a balanced tree by recursively bisecting each rectangle at
+    /// its median, splitting the remaining cut budget as evenly as
+    /// possible. Depth stays O(log num_cuts), unlike `random_tree`'s
+    /// deliberately skewed trees.
+    pub fn balanced_tree(width: usize, height: usize, num_cuts: usize) -> SlicingTree {
+        let mut tree = SlicingTree::new(width, height);
+        tree.bisect(0, num_cuts, None);
+        tree
+    }
+
+    fn bisect(&mut self, node: usize, cuts: usize, parent_cut: Option<Cut>) {
+        if cuts == 0 {
+            return;
+        }
+
+        let cut = parent_cut.map_or(Cut::Vertical, |c| c.opposite());
+
+        let rect_idx = self.nodes[node].rect.unwrap();
+        let rect = self.data[rect_idx];
+        let (left, right) = rect.cut(cut);
+
+        self.data[rect_idx] = left;
+        let left_rect = rect_idx;
+        let right_rect = self.push_rect(right);
+
+        let new_left = Node::new(None, Some(left_rect), None, None, Some(node));
+        let left_idx = self.push_node(new_left);
+
+        let new_right = Node::new(None, Some(right_rect), None, None, Some(node));
+        let right_idx = self.push_node(new_right);
+
+        let parent = &mut self.nodes[node];
+        parent.rect = None;
+        parent.cut = Some(cut);
+        parent.left = Some(left_idx);
+        parent.right = Some(right_idx);
+
+        let remaining = cuts - 1;
+        let left_cuts = remaining / 2;
+        let right_cuts = remaining - left_cuts;
+
+        self.bisect(left_idx, left_cuts, Some(cut));
+        self.bisect(right_idx, right_cuts, Some(cut));
+    }
+
     pub fn push_rect(&mut self, rect: Rect) -> usize {
         self.data.push(rect);
         self.data.len() - 1
@@ -210,39 +273,75 @@ impl SlicingTree {
         self.nodes.len() - 1
     }
 
+    /// Iterative (explicit-stack) postorder walk, so trees with depth on
+    /// the order of tens of thousands of cuts don't overflow the stack.
     pub fn postorder(&self) -> NPE {
         let mut data = Vec::new();
-        self.postorder_rec(0, &mut data);
-        NPE::new(data)
-    }
+        let mut stack = vec![0usize];
+        let mut last_visited = None;
 
-    fn postorder_rec(&self, root: usize, data: &mut Vec<TreeItem>) {
-        let root_node = self.nodes[root];
-        if let Some(rect) = root_node.rect {
-            data.push(TreeItem::Rect(rect));
-            return;
-        }
+        while let Some(&node_idx) = stack.last() {
+            let node = self.nodes[node_idx];
+
+            if let Some(rect) = node.rect {
+                data.push(TreeItem::Rect(rect));
+                stack.pop();
+                last_visited = Some(node_idx);
+                continue;
+            }
 
-        self.postorder_rec(root_node.left.unwrap(), data);
-        self.postorder_rec(root_node.right.unwrap(), data);
+            let left = node.left.unwrap();
+            let right = node.right.unwrap();
 
-        if let Some(cut) = root_node.cut {
-            data.push(TreeItem::Cut(cut));
+            if last_visited == Some(right) {
+                data.push(TreeItem::Cut(node.cut.unwrap()));
+                stack.pop();
+                last_visited = Some(node_idx);
+            } else if last_visited == Some(left) {
+                stack.push(right);
+            } else {
+                stack.push(left);
+            }
         }
+
+        NPE::new(data)
     }
 
+    /// Iterative (explicit-stack) bounding-box evaluation, mirroring
+    /// `postorder`'s traversal so large trees can be re-evaluated without
+    /// recursing.
     pub fn aabb(&self, root: usize) -> Rect {
-        let node = self.nodes[root];
-        if let Some(rect) = node.rect {
-            return self.data[rect];
-        }
-
-        let left = self.aabb(node.left.unwrap());
-        let right = self.aabb(node.right.unwrap());
+        let mut stack = vec![root];
+        let mut last_visited = None;
+        let mut values: Vec<Rect> = Vec::new();
+
+        while let Some(&node_idx) = stack.last() {
+            let node = self.nodes[node_idx];
+
+            if let Some(rect) = node.rect {
+                values.push(self.data[rect]);
+                stack.pop();
+                last_visited = Some(node_idx);
+                continue;
+            }
 
-        let cut = node.cut.unwrap();
+            let left = node.left.unwrap();
+            let right = node.right.unwrap();
+
+            if last_visited == Some(right) {
+                let right_rect = values.pop().unwrap();
+                let left_rect = values.pop().unwrap();
+                values.push(Rect::aabb(left_rect, right_rect, node.cut.unwrap()));
+                stack.pop();
+                last_visited = Some(node_idx);
+            } else if last_visited == Some(left) {
+                stack.push(right);
+            } else {
+                stack.push(left);
+            }
+        }
 
-        Rect::aabb(left, right, cut)
+        values.pop().unwrap()
     }
 
     pub fn print_as_problem(&self) {
@@ -332,14 +431,88 @@ impl NPE {
         operands[0]
     }
 
-    pub fn perturb(&mut self, iterations: usize) {
+    /// Walks `expr` assigning an `(x, y)` lower-left coordinate to every
+    /// operand: each `Cut::Vertical` offsets the right subtree by the left
+    /// subtree's width, `Cut::Horizontal` by its height.
+    pub fn pack(&self, rects: &[Rect]) -> Packing {
+        // Per not-yet-combined subtree: the `(operand index, (x, y))` pairs
+        // assigned so far, relative to that subtree's own origin.
+        type PositionList = Vec<(usize, (usize, usize))>;
+        // One stack entry per not-yet-combined subtree: its bounding box,
+        // and the positions of the operands inside it.
+        type PartialPacking = Vec<(Rect, PositionList)>;
+        let mut stack: PartialPacking = Vec::new();
+
+        for item in &self.expr {
+            match item {
+                TreeItem::Rect(i) => {
+                    stack.push((rects[*i], vec![(*i, (0, 0))]));
+                }
+                TreeItem::Cut(cut) => {
+                    let (right_rect, right_pos) = stack.pop().unwrap();
+                    let (left_rect, left_pos) = stack.pop().unwrap();
+
+                    let (dx, dy) = match cut {
+                        Cut::Vertical => (left_rect.width(), 0),
+                        Cut::Horizontal => (0, left_rect.height()),
+                    };
+
+                    let mut combined = left_pos;
+                    combined.extend(
+                        right_pos
+                            .into_iter()
+                            .map(|(idx, (x, y))| (idx, (x + dx, y + dy))),
+                    );
+
+                    stack.push((Rect::aabb(left_rect, right_rect, *cut), combined));
+                }
+            }
+        }
+
+        let (_, positions) = stack.pop().unwrap();
+        let mut packing = vec![(Rect::new(0, 0), (0, 0)); rects.len()];
+        for (idx, pos) in positions {
+            packing[idx] = (rects[idx], pos);
+        }
+
+        packing
+    }
+
+    /// Builds a segment tree that evaluates `aabb` incrementally. Keep the
+    /// tree around across perturbations and call `NpeSegTree::update` after
+    /// each `swap` instead of rebuilding from scratch.
+    pub fn seg_tree(&self, rects: &[Rect]) -> NpeSegTree {
+        NpeSegTree::new(&self.expr, rects)
+    }
+
+    /// Builds an order-statistic companion for O(log n) `m1`/`m2`
+    /// selection and `m3` ballot checks on large `expr`s. See [`NpeIndex`].
+    pub fn index(&self) -> NpeIndex {
+        NpeIndex::new(self)
+    }
+
+    /// Builds the Stockmeyer shape-curve DP for this NPE's topology, so the
+    /// cost can account for the best realizable orientation of every block
+    /// instead of a single fixed one. See [`ShapeTree`].
+    pub fn shape_tree(&self, candidates: &[Vec<(usize, usize)>]) -> ShapeTree {
+        ShapeTree::build(self, candidates)
+    }
+
+    /// Runs `iterations` random M1/M2/M3 moves, selecting the operand/chain
+    /// for each one through an internally maintained [`NpeIndex`] (O(log n))
+    /// instead of linearly scanning `expr`. Returns every `expr` position
+    /// whose entry changed, in the order the moves touched them, so a
+    /// caller maintaining its own [`NpeSegTree`] can replay
+    /// `NpeSegTree::update` for each one instead of rebuilding from scratch.
+    pub fn perturb(&mut self, iterations: usize) -> Vec<usize> {
         use rand::{seq::SliceRandom, thread_rng, Rng};
         static CHOICES: &[u8] = &[1, 2, 3];
         let mut rng = thread_rng();
 
-        let mut num_chains = self.chains().count();
+        let mut index = self.index();
         // let num_operands = self.count_operands();
         let num_operators = self.count_operators();
+        let mut touched = Vec::new();
 
         for _ in 0..iterations {
             match *CHOICES.choose(&mut rng).unwrap() {
@@ -347,28 +520,37 @@ impl NPE {
                 1 => {
                     // println!("1");
                     let a: usize = rng.gen_range(0, num_operators - 1);
-                    self.m1(a);
+                    let (a_idx, b_idx) = self.m1_indexed(a, &index);
+                    touched.push(a_idx);
+                    touched.push(b_idx);
                 }
                 // M2
                 2 => {
                     // println!("2");
-                    let n = rng.gen_range(0, num_chains);
+                    let n = rng.gen_range(0, index.num_chains());
                     // println!("{} chain", n);
-                    self.m2(n);
-                    // let (a, b) = self.chains().nth(n).unwrap();
+                    let (a, b) = self.m2_indexed(n, &index);
+                    touched.extend(a..b);
                 }
                 // M3
                 3 => {
                     // println!("3");
-                    self.m3();
-                    num_chains = self.chains().count();
+                    if let Some((i, j)) = self.m3_indexed(&index) {
+                        index.on_adjacent_swap(&self.expr, i);
+                        touched.push(i);
+                        touched.push(j);
+                    }
                 }
                 _ => unreachable!(),
             }
         }
+
+        touched
     }
 
-    pub fn m1(&mut self, a: usize) {
+    /// Swaps the `a`-th operand with the operand right after it. Returns
+    /// the two touched positions.
+    pub fn m1(&mut self, a: usize) -> (usize, usize) {
         let mut iter =
             self.expr
                 .iter()
@@ -381,13 +563,30 @@ impl NPE {
         // println!("M1: {} <-> {}", a_idx, b_idx);
 
         self.swap(a_idx, b_idx);
+
+        (a_idx, b_idx)
     }
 
-    pub fn m2(&mut self, n: usize) {
+    /// Same as [`NPE::m1`], but selects both operands from `index` in
+    /// O(log n) instead of linearly scanning `expr`. Swapping two operands
+    /// doesn't change which positions hold operands, so `index` itself
+    /// needs no update afterwards.
+    pub fn m1_indexed(&mut self, a: usize, index: &NpeIndex) -> (usize, usize) {
+        let a_idx = index.nth_operand(a);
+        let b_idx = index.nth_operand(a + 1);
+
+        self.swap(a_idx, b_idx);
+
+        (a_idx, b_idx)
+    }
+
+    /// Flips every cut in the n-th chain. Returns the `[a, b)` range of
+    /// touched positions.
+    pub fn m2(&mut self, n: usize) -> (usize, usize) {
         let (a, b) = self.chains().nth(n).unwrap();
-        
+
         let chain = &mut self.expr[a..b];
-        
+
         // println!("M2: ({}, {}) {:?}", a, b, chain);
 
         for e in chain {
@@ -398,6 +597,24 @@ impl NPE {
                 _ => {}
             }
         }
+
+        (a, b)
+    }
+
+    /// Same as [`NPE::m2`], but looks up the chain's `[a, b)` bounds from
+    /// `index` in O(log n) instead of walking `chains()`. Flipping cuts in
+    /// place doesn't change which positions hold operands, so `index`
+    /// itself needs no update afterwards.
+    pub fn m2_indexed(&mut self, n: usize, index: &NpeIndex) -> (usize, usize) {
+        let (a, b) = index.nth_chain(n);
+
+        for e in &mut self.expr[a..b] {
+            if let TreeItem::Cut(cut) = e {
+                *cut = cut.opposite();
+            }
+        }
+
+        (a, b)
     }
 
     // pub fn print_npe(&self) {
@@ -408,7 +625,9 @@ impl NPE {
     //     println!("{:?}", self.expr[self.expr.len() - 1]);
     // }
 
-    pub fn m3(&mut self) {
+    /// Tries an adjacent rect/cut swap. Returns the swapped positions if one
+    /// was found and accepted, or `None` if no candidate window normalized.
+    pub fn m3(&mut self) -> Option<(usize, usize)> {
         use rand::{seq::SliceRandom, thread_rng};
 
         let mut windows: Vec<_> = self
@@ -421,12 +640,12 @@ impl NPE {
                         Some(i)
                     } else {
                         None
-                    }, // TODO: Figure out: 
+                    }, // TODO: Figure out:
                     _ => None,
                 }
             })
             .collect();
-        
+
         windows.shuffle(&mut thread_rng());
 
         for i in windows {
@@ -438,10 +657,59 @@ impl NPE {
                     self.swap(i, i + 1);
                 } else {
                     self.calculate_ballot();
-                    break;
+                    return Some((i, i + 1));
                 }
             }
         }
+
+        None
+    }
+
+    /// Same as [`NPE::m3`], but checks the ballot condition against `index`'s
+    /// Fenwick-backed prefix counts (O(log n) per candidate) instead of the
+    /// eagerly-recomputed `self.ballot` Vec, so an accepted swap doesn't pay
+    /// a full `calculate_ballot` rescan. `index` is left untouched here —
+    /// the caller is expected to follow a successful swap with
+    /// `NpeIndex::on_adjacent_swap`, as `perturb` does.
+    ///
+    /// Candidate windows are still found with a linear `expr` scan: picking
+    /// a uniformly random valid window in O(log n) would need its own
+    /// Fenwick over boundary positions, which is more machinery than this
+    /// move's ballot check alone justifies.
+    pub fn m3_indexed(&mut self, index: &NpeIndex) -> Option<(usize, usize)> {
+        use rand::{seq::SliceRandom, thread_rng};
+
+        let mut windows: Vec<_> = self
+            .expr
+            .windows(2)
+            .enumerate()
+            .filter_map(|(i, s)| match s {
+                &[a, b] => {
+                    if a.is_rect() && b.is_cut() || a.is_cut() && b.is_rect() {
+                        Some(i)
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+
+        windows.shuffle(&mut thread_rng());
+
+        for i in windows {
+            if self.is_swap_normalized_indexed(index, i, i + 1) {
+                self.swap(i, i + 1);
+
+                if !self.is_normalized(i.saturating_sub(1), i + 2) {
+                    self.swap(i, i + 1);
+                } else {
+                    return Some((i, i + 1));
+                }
+            }
+        }
+
+        None
     }
 
     pub fn chains(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
@@ -532,6 +800,10 @@ impl NPE {
         2 * self.ballot[b].1 < a
     }
 
+    fn satisfies_ballot_indexed(&self, index: &NpeIndex, a: usize, b: usize) -> bool {
+        2 * index.ballot(b).1 < a
+    }
+
     pub fn is_normalized(&self, a: usize, b: usize) -> bool {
         self.expr[a..=b].windows(2).all(|w| match w {
             &[a, b] => {
@@ -553,13 +825,18 @@ impl NPE {
 
         // let (operand, operator) = if self.expr[a].is_cut() {
         //     (b, a)
-        // } else { 
+        // } else {
         //     (a, b)
         // };
 
         // false
     }
 
+    fn is_swap_normalized_indexed(&self, index: &NpeIndex, a: usize, b: usize) -> bool {
+        self.satisfies_ballot_indexed(index, a, b)
+            && self.is_normalized(a.saturating_sub(1), b.saturating_add(1))
+    }
+
     pub fn swap(&mut self, a: usize, b: usize) {
         self.expr.swap(a, b);
     }
@@ -636,3 +913,48 @@ impl fmt::Display for NPE {
         write!(f, "{:?}", self.expr[self.expr.len() - 1])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balanced_tree_iterative_aabb_matches_postorder_aabb() {
+        let tree = SlicingTree::balanced_tree(1_000, 1_000, 5_000);
+
+        let via_postorder = tree.postorder().aabb(&tree.data);
+        let via_iterative_walk = tree.aabb(0);
+
+        assert_eq!(via_postorder, via_iterative_walk);
+    }
+
+    #[test]
+    fn balanced_tree_depth_stays_logarithmic() {
+        // `random_tree`'s deliberately alternating cuts can reach depth
+        // equal to `num_cuts`; this is the O(n) depth `balanced_tree` exists
+        // to avoid for the large instances that stack-overflowed a
+        // recursive traversal.
+        let num_cuts = 10_000;
+        let tree = SlicingTree::balanced_tree(1_000, 1_000, num_cuts);
+
+        let depth_of = |mut idx: usize| {
+            let mut depth = 0;
+            while let Some(parent) = tree.nodes[idx].parent {
+                idx = parent;
+                depth += 1;
+            }
+            depth
+        };
+
+        let max_depth = (0..tree.nodes.len()).map(depth_of).max().unwrap();
+        let bound = 2 * (num_cuts as f64).log2().ceil() as usize + 4;
+
+        assert!(
+            max_depth < bound,
+            "max depth {} too deep for {} cuts (bound {})",
+            max_depth,
+            num_cuts,
+            bound
+        );
+    }
+}
@@ -1,20 +1,58 @@
 use std::io::{Write, stdout};
 use rayon::prelude::*;
 
-use slicing_trees::{SlicingTree, NPE, Rect};
+use slicing_trees::{hpwl, rotations, Net, NpeSegTree, Rect, SlicingTree, NPE};
 
 fn p(delta_cost: f32, temp: f32) -> f32 {
     (-delta_cost / temp).exp()
 }
 
-fn simulated_annealing(npe: NPE, time: usize, k: usize, temp_epsilon: f32, temp_reduction: f32, rects: &[Rect]) -> NPE {
+/// The floorplanning objective's inputs, grouped so `simulated_annealing`
+/// doesn't have to take them as five separate arguments.
+struct CostParams<'a> {
+    rects: &'a [Rect],
+    nets: &'a [Net],
+    alpha: f32,
+    beta: f32,
+    // When set, area is scored by `ShapeTree::optimal_area` over these
+    // per-operand orientation candidates (one full rebuild per candidate
+    // NPE) instead of the fixed-orientation `NpeSegTree`. Lets rotatable
+    // blocks get credit for their best realizable orientation.
+    shape_candidates: Option<&'a [Vec<(usize, usize)>]>,
+}
+
+/// The area term of `cost`: `NpeSegTree::cost` (O(log n), one fixed
+/// orientation) when `cost.shape_candidates` is unset, or
+/// `ShapeTree::optimal_area` (O(n), best realizable orientation) when it is.
+fn area(npe: &NPE, seg: &NpeSegTree, cost: &CostParams) -> f32 {
+    match cost.shape_candidates {
+        Some(candidates) => npe.shape_tree(candidates).optimal_area(),
+        None => seg.cost(),
+    }
+}
+
+fn simulated_annealing(
+    npe: NPE,
+    cost: &CostParams,
+    time: usize,
+    k: usize,
+    temp_epsilon: f32,
+    temp_reduction: f32,
+) -> NPE {
     let n = npe.count_operands() * k;
-    
+
     let mut temp = 1.0;
     let mut best = npe;
-    let mut best_cost = best.aabb(rects).cost();
+    // Kept in sync with `best` via `NpeSegTree::update` on the positions
+    // `perturb` touches, so the area half of `cost` is an O(log n) lookup
+    // instead of a full `aabb` walk on every candidate. Unused when
+    // `cost.shape_candidates` is set, since `area` rebuilds a `ShapeTree`
+    // from scratch for that mode instead.
+    let mut best_seg = best.seg_tree(cost.rects);
+    let mut best_cost =
+        cost.alpha * area(&best, &best_seg, cost) + cost.beta * hpwl(&best.pack(cost.rects), cost.nets);
     // let mut rejected = 0;
-    
+
     for t in 0..time {
         let mut uphill = 0;
         let mut iters = 0;
@@ -24,17 +62,23 @@ fn simulated_annealing(npe: NPE, time: usize, k: usize, temp_epsilon: f32, temp_
             iters += 1;
 
             let mut candidate = best.clone();
-            candidate.perturb(1); // Get a neighbor
+            let mut seg = best_seg.clone();
+            for pos in candidate.perturb(1) {
+                // Get a neighbor, keeping `seg` in sync with it.
+                seg.update(pos, candidate.expr[pos], cost.rects);
+            }
 
-            let new_cost = candidate.aabb(rects).cost();
+            let new_cost = cost.alpha * area(&candidate, &seg, cost)
+                + cost.beta * hpwl(&candidate.pack(cost.rects), cost.nets);
             let delta = new_cost - best_cost;
-            
-            if delta <= 0.0 || rand::random::<f32>() < p(delta, temp) {    
+
+            if delta <= 0.0 || rand::random::<f32>() < p(delta, temp) {
                 if delta > 0.0 {
                     uphill += 1;
                 }
-                
+
                 best = candidate;
+                best_seg = seg;
                 best_cost = new_cost;
             } else {
                 rejected += 1;
@@ -43,9 +87,9 @@ fn simulated_annealing(npe: NPE, time: usize, k: usize, temp_epsilon: f32, temp_
             if uphill > n || iters > 2 * n {
                 break;
             }
-            
+
         }
-        
+
         // print!("Temp: {}\r", temp);
         // stdout().flush();
 
@@ -63,7 +107,9 @@ fn simulated_annealing(npe: NPE, time: usize, k: usize, temp_epsilon: f32, temp_
 
 
 fn main() {
-    let tree = SlicingTree::random_tree(10_000, 10_000, 49);
+    // `random_tree` deliberately alternates cuts into a skewed, O(n)-deep
+    // tree; at this scale `balanced_tree` keeps depth O(log n) instead.
+    let tree = SlicingTree::balanced_tree(10_000, 10_000, 49);
     let mut npe = tree.postorder();
 
     let pre = npe.aabb(&tree.data);
@@ -74,8 +120,23 @@ fn main() {
     let bad_aabb = npe.aabb(&tree.data);
     println!("{:?} -> {}: {}", bad_aabb, bad_aabb.cost(), npe);
     
+    // Chain each block to the next one as a stand-in connectivity graph, and
+    // trade off area against routed wirelength.
+    let nets: Vec<Net> = (0..tree.data.len() - 1).map(|i| vec![i, i + 1]).collect();
+    // Every block may also be placed rotated 90 degrees, so score candidates
+    // by their best realizable orientation rather than the one `postorder`
+    // happened to assign.
+    let shape_candidates = rotations(&tree.data);
+    let cost = CostParams {
+        rects: &tree.data,
+        nets: &nets,
+        alpha: 1.0,
+        beta: 0.0001,
+        shape_candidates: Some(&shape_candidates),
+    };
+
     let best = (0..100).into_par_iter().map(|_| {
-        simulated_annealing(npe.clone(), 1_000_000, 3, 0.05, 0.9999, &tree.data)
+        simulated_annealing(npe.clone(), &cost, 1_000_000, 3, 0.05, 0.9999)
     }).min_by_key(|e| {
         e.aabb(&tree.data).cost() as isize
     }).unwrap();
@@ -0,0 +1,55 @@
+use crate::{Rect, NPE};
+
+/// A net: the indices of the blocks it connects.
+pub type Net = Vec<usize>;
+
+/// A placed layout: every block's `Rect` and its `(x, y)` lower-left
+/// corner, indexed by block. See [`NPE::pack`](crate::NPE::pack).
+pub type Packing = Vec<(Rect, (usize, usize))>;
+
+/// The `(width, height)` bounding box spanned by a packing, without
+/// re-walking the NPE that produced it.
+fn extent(packing: &[(Rect, (usize, usize))]) -> (usize, usize) {
+    packing.iter().fold((0, 0), |(w, h), (rect, (x, y))| {
+        (w.max(x + rect.width()), h.max(y + rect.height()))
+    })
+}
+
+/// Half-perimeter wirelength: for each net, the span of the bounding box of
+/// its blocks' center points, summed over all nets.
+pub fn hpwl(packing: &[(Rect, (usize, usize))], nets: &[Net]) -> f32 {
+    nets.iter()
+        .map(|net| {
+            let mut min_x = usize::MAX;
+            let mut max_x = 0;
+            let mut min_y = usize::MAX;
+            let mut max_y = 0;
+
+            for &block in net {
+                let (rect, (x, y)) = packing[block];
+                let cx = x + rect.width() / 2;
+                let cy = y + rect.height() / 2;
+
+                min_x = min_x.min(cx);
+                max_x = max_x.max(cx);
+                min_y = min_y.min(cy);
+                max_y = max_y.max(cy);
+            }
+
+            ((max_x - min_x) + (max_y - min_y)) as f32
+        })
+        .sum()
+}
+
+/// `alpha * area + beta * HPWL`, the standard floorplanning objective that
+/// trades off area against routed wirelength. Walks `expr` once (via
+/// `pack`) rather than once for the packing and again for `aabb`, since the
+/// bounding box is just the packing's extent.
+pub fn combined_cost(npe: &NPE, rects: &[Rect], nets: &[Net], alpha: f32, beta: f32) -> f32 {
+    let packing = npe.pack(rects);
+    let (width, height) = extent(&packing);
+    let area = (width * height) as f32;
+    let wirelength = hpwl(&packing, nets);
+
+    alpha * area + beta * wirelength
+}
@@ -0,0 +1,213 @@
+use crate::{Cut, Rect, TreeItem};
+
+// A stack slot that may still depend on operands supplied by whatever comes
+// before this segment. `Hole(i)` is the i-th operand pulled from the context
+// to the left, counting from the one popped first (i.e. closest to this
+// segment).
+#[derive(Clone, Debug)]
+enum Cell {
+    Known(Rect),
+    Hole(usize),
+    Pending(Cut, Box<Cell>, Box<Cell>),
+}
+
+impl Cell {
+    fn known(&self) -> Option<Rect> {
+        match self {
+            Cell::Known(r) => Some(*r),
+            _ => None,
+        }
+    }
+
+    // Replace every Hole(i) with `subst(i)`, folding any cell that becomes
+    // fully concrete back into a single Known(Rect).
+    fn substitute(&self, subst: &impl Fn(usize) -> Cell) -> Cell {
+        match self {
+            Cell::Known(r) => Cell::Known(*r),
+            Cell::Hole(i) => subst(*i),
+            Cell::Pending(cut, l, r) => {
+                let l = l.substitute(subst);
+                let r = r.substitute(subst);
+                match (l.known(), r.known()) {
+                    (Some(l), Some(r)) => Cell::Known(Rect::aabb(l, r, *cut)),
+                    _ => Cell::Pending(*cut, Box::new(l), Box::new(r)),
+                }
+            }
+        }
+    }
+}
+
+// One node of the segment tree over `expr`: `stack` is what the segment
+// leaves behind once evaluated, and `deficit` is how many operands it still
+// needs to borrow from whatever precedes it.
+#[derive(Clone, Debug, Default)]
+struct SegNode {
+    deficit: usize,
+    stack: Vec<Cell>,
+}
+
+impl SegNode {
+    fn leaf(item: TreeItem, data: &[Rect]) -> Self {
+        match item {
+            TreeItem::Rect(i) => SegNode {
+                deficit: 0,
+                stack: vec![Cell::Known(data[i])],
+            },
+            TreeItem::Cut(cut) => SegNode {
+                deficit: 2,
+                stack: vec![Cell::Pending(
+                    cut,
+                    Box::new(Cell::Hole(0)),
+                    Box::new(Cell::Hole(1)),
+                )],
+            },
+        }
+    }
+
+    fn combine(left: &SegNode, right: &SegNode) -> SegNode {
+        let available = left.stack.len();
+        let borrowed = available.min(right.deficit);
+        let overflow = right.deficit - borrowed;
+
+        let subst = |i: usize| -> Cell {
+            if i < borrowed {
+                left.stack[available - 1 - i].clone()
+            } else {
+                Cell::Hole(left.deficit + (i - borrowed))
+            }
+        };
+
+        let mut stack: Vec<Cell> = left.stack[..available - borrowed].to_vec();
+        stack.extend(right.stack.iter().map(|c| c.substitute(&subst)));
+
+        SegNode {
+            deficit: left.deficit + overflow,
+            stack,
+        }
+    }
+}
+
+/// A segment tree over an [`NPE`](crate::NPE)'s Polish expression that
+/// evaluates the bounding box incrementally: point updates (from `m1`/`m3`
+/// operand swaps or `m2` cut flips) touch O(log n) nodes instead of
+/// re-running the whole stack machine.
+#[derive(Clone)]
+pub struct NpeSegTree {
+    // Padded up to a power of two: the array-backed "leaves at size..2*size"
+    // layout only preserves left-to-right evaluation order for a balanced
+    // (power-of-two) tree, which our combine needs since it isn't
+    // commutative. Padding leaves sit past `n` and stay the default
+    // (deficit 0, empty stack), which is exactly the identity for combine.
+    size: usize,
+    nodes: Vec<SegNode>,
+}
+
+impl NpeSegTree {
+    pub fn new(expr: &[TreeItem], data: &[Rect]) -> Self {
+        let n = expr.len();
+        let size = n.next_power_of_two().max(1);
+        let mut nodes = vec![SegNode::default(); 2 * size];
+
+        for i in 0..n {
+            nodes[size + i] = SegNode::leaf(expr[i], data);
+        }
+
+        for i in (1..size).rev() {
+            nodes[i] = SegNode::combine(&nodes[2 * i], &nodes[2 * i + 1]);
+        }
+
+        Self { size, nodes }
+    }
+
+    pub fn update(&mut self, pos: usize, item: TreeItem, data: &[Rect]) {
+        let mut i = self.size + pos;
+        self.nodes[i] = SegNode::leaf(item, data);
+
+        while i > 1 {
+            i /= 2;
+            self.nodes[i] = SegNode::combine(&self.nodes[2 * i], &self.nodes[2 * i + 1]);
+        }
+    }
+
+    /// The bounding box for a well-formed NPE: the root has no remaining
+    /// deficit and a single resolved operand on its stack.
+    pub fn root(&self) -> Rect {
+        let root = &self.nodes[1];
+        debug_assert_eq!(root.deficit, 0);
+        debug_assert_eq!(root.stack.len(), 1);
+
+        root.stack[0].known().expect("unresolved NPE root")
+    }
+
+    pub fn cost(&self) -> f32 {
+        self.root().cost()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NPE;
+
+    fn rect(width: usize, height: usize) -> Rect {
+        Rect::new(width, height)
+    }
+
+    #[test]
+    fn matches_full_scan_aabb() {
+        // ((r0 V r1) H r2)
+        let data = vec![rect(3, 4), rect(5, 2), rect(6, 6)];
+        let expr = vec![
+            TreeItem::Rect(0),
+            TreeItem::Rect(1),
+            TreeItem::Cut(Cut::Vertical),
+            TreeItem::Rect(2),
+            TreeItem::Cut(Cut::Horizontal),
+        ];
+
+        let seg = NpeSegTree::new(&expr, &data);
+        let expected = NPE::new(expr).aabb(&data);
+        assert_eq!(seg.root(), expected);
+    }
+
+    #[test]
+    fn odd_leaf_count_still_evaluates_left_to_right() {
+        // 3 leaves (expr.len() == 5, not a power of two) is what caught the
+        // original padding-order bug: the naive "leaves at size..2*size"
+        // layout combined them out of order for non-power-of-two sizes.
+        let data = vec![rect(1, 10), rect(2, 1), rect(10, 1)];
+        let expr = vec![
+            TreeItem::Rect(0),
+            TreeItem::Rect(1),
+            TreeItem::Cut(Cut::Horizontal),
+            TreeItem::Rect(2),
+            TreeItem::Cut(Cut::Vertical),
+        ];
+
+        let seg = NpeSegTree::new(&expr, &data);
+        let expected = NPE::new(expr).aabb(&data);
+        assert_eq!(seg.root(), expected);
+    }
+
+    #[test]
+    fn update_keeps_root_in_sync_with_full_rebuild() {
+        let data = vec![rect(3, 4), rect(5, 2), rect(6, 6), rect(1, 9)];
+        let mut expr = vec![
+            TreeItem::Rect(0),
+            TreeItem::Rect(1),
+            TreeItem::Cut(Cut::Vertical),
+            TreeItem::Rect(2),
+            TreeItem::Cut(Cut::Horizontal),
+            TreeItem::Rect(3),
+            TreeItem::Cut(Cut::Vertical),
+        ];
+
+        let mut seg = NpeSegTree::new(&expr, &data);
+
+        expr[4] = TreeItem::Cut(Cut::Vertical);
+        seg.update(4, expr[4], &data);
+
+        let expected = NPE::new(expr).aabb(&data);
+        assert_eq!(seg.root(), expected);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{Cut, Rect};
+
+/// A Pareto-optimal staircase of `(width, height)` implementations: sorted
+/// by strictly increasing width and strictly decreasing height, so neither
+/// point dominates another.
+#[derive(Clone, Debug, Default)]
+pub struct ShapeCurve {
+    points: Vec<(usize, usize)>,
+}
+
+impl ShapeCurve {
+    pub fn points(&self) -> &[(usize, usize)] {
+        &self.points
+    }
+
+    /// The point index and value minimizing `width * height`.
+    pub fn min_area(&self) -> (usize, (usize, usize)) {
+        self.points
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, &(w, h))| w * h)
+            .map(|(i, &p)| (i, p))
+            .unwrap()
+    }
+
+    fn from_candidates_indexed(candidates: &[(usize, usize)]) -> (ShapeCurve, Vec<Choice>) {
+        let raw = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, &(w, h))| ((w, h), Choice::Leaf(i)))
+            .collect();
+
+        prune(raw)
+    }
+
+    // Vertical cuts sum widths for a shared height bound; horizontal cuts
+    // are the same sweep with width/height swapped.
+    fn merge(left: &ShapeCurve, right: &ShapeCurve, cut: Cut) -> (ShapeCurve, Vec<Choice>) {
+        match cut {
+            Cut::Vertical => merge_sum_width(left, right),
+            Cut::Horizontal => {
+                let lt = transpose_curve(left);
+                let rt = transpose_curve(right);
+                let (curve, choices) = merge_sum_width(&lt, &rt);
+
+                let choices: Vec<Choice> = choices
+                    .into_iter()
+                    .map(|c| match c {
+                        Choice::Leaf(i) => Choice::Leaf(i),
+                        Choice::Cut(li, ri) => {
+                            Choice::Cut(left.points.len() - 1 - li, right.points.len() - 1 - ri)
+                        }
+                    })
+                    .collect();
+
+                transpose_with_choices(&curve, &choices)
+            }
+        }
+    }
+}
+
+fn transpose_curve(curve: &ShapeCurve) -> ShapeCurve {
+    let mut points: Vec<(usize, usize)> = curve.points.iter().map(|&(w, h)| (h, w)).collect();
+    points.reverse();
+    ShapeCurve { points }
+}
+
+fn transpose_with_choices(curve: &ShapeCurve, choices: &[Choice]) -> (ShapeCurve, Vec<Choice>) {
+    let mut points: Vec<(usize, usize)> = curve.points.iter().map(|&(w, h)| (h, w)).collect();
+    points.reverse();
+    let mut choices = choices.to_vec();
+    choices.reverse();
+
+    (ShapeCurve { points }, choices)
+}
+
+fn merge_sum_width(left: &ShapeCurve, right: &ShapeCurve) -> (ShapeCurve, Vec<Choice>) {
+    let mut heights: Vec<usize> = left
+        .points
+        .iter()
+        .chain(right.points.iter())
+        .map(|&(_, h)| h)
+        .collect();
+    heights.sort_unstable_by(|a, b| b.cmp(a));
+    heights.dedup();
+
+    let mut li = 0;
+    let mut ri = 0;
+    let mut raw = Vec::with_capacity(heights.len());
+
+    for h in heights {
+        while li + 1 < left.points.len() && left.points[li].1 > h {
+            li += 1;
+        }
+        while ri + 1 < right.points.len() && right.points[ri].1 > h {
+            ri += 1;
+        }
+
+        if left.points[li].1 > h || right.points[ri].1 > h {
+            continue;
+        }
+
+        let w = left.points[li].0 + right.points[ri].0;
+        let max_h = left.points[li].1.max(right.points[ri].1);
+        raw.push(((w, max_h), Choice::Cut(li, ri)));
+    }
+
+    prune(raw)
+}
+
+// Sort ascending by width and keep only strictly-decreasing-height points,
+// discarding everything dominated.
+fn prune(mut raw: Vec<((usize, usize), Choice)>) -> (ShapeCurve, Vec<Choice>) {
+    raw.sort_by(|a, b| a.0 .0.cmp(&b.0 .0).then(a.0 .1.cmp(&b.0 .1)));
+
+    let mut points = Vec::new();
+    let mut choices = Vec::new();
+    let mut min_height = usize::MAX;
+
+    for ((w, h), choice) in raw {
+        if h < min_height {
+            points.push((w, h));
+            choices.push(choice);
+            min_height = h;
+        }
+    }
+
+    (ShapeCurve { points }, choices)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Choice {
+    Leaf(usize),
+    Cut(usize, usize),
+}
+
+/// The shape-curve DP result for one [`NPE`](crate::NPE), built bottom-up
+/// over the Polish expression the same way `NPE::aabb` walks it, but
+/// carrying a whole curve of realizable `(width, height)` options per
+/// subtree instead of a single fixed box.
+pub enum ShapeTree {
+    Leaf {
+        operand: usize,
+        curve: ShapeCurve,
+        choices: Vec<Choice>,
+    },
+    Cut {
+        left: Box<ShapeTree>,
+        right: Box<ShapeTree>,
+        curve: ShapeCurve,
+        choices: Vec<Choice>,
+    },
+}
+
+impl ShapeTree {
+    /// Builds the shape-curve DP for `npe`. `candidates[i]` is the list of
+    /// realizable `(width, height)` implementations for operand `i` — at
+    /// minimum the rectangle and its rotation.
+    pub fn build(npe: &crate::NPE, candidates: &[Vec<(usize, usize)>]) -> ShapeTree {
+        let mut stack: Vec<ShapeTree> = Vec::new();
+
+        for item in &npe.expr {
+            match item {
+                crate::TreeItem::Rect(i) => {
+                    let (curve, choices) = ShapeCurve::from_candidates_indexed(&candidates[*i]);
+                    stack.push(ShapeTree::Leaf {
+                        operand: *i,
+                        curve,
+                        choices,
+                    });
+                }
+                crate::TreeItem::Cut(cut) => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    let (curve, choices) = ShapeCurve::merge(left.curve(), right.curve(), *cut);
+
+                    stack.push(ShapeTree::Cut {
+                        left: Box::new(left),
+                        right: Box::new(right),
+                        curve,
+                        choices,
+                    });
+                }
+            }
+        }
+
+        stack.pop().expect("empty NPE")
+    }
+
+    pub fn curve(&self) -> &ShapeCurve {
+        match self {
+            ShapeTree::Leaf { curve, .. } => curve,
+            ShapeTree::Cut { curve, .. } => curve,
+        }
+    }
+
+    /// The minimum realizable area over every choice of orientation.
+    pub fn optimal_area(&self) -> f32 {
+        let (_, (w, h)) = self.curve().min_area();
+        (w * h) as f32
+    }
+
+    /// The `(operand, (width, height))` orientation chosen for each leaf to
+    /// realize `optimal_area`.
+    pub fn orientations(&self) -> Vec<(usize, (usize, usize))> {
+        let mut out = Vec::new();
+        let (point, _) = self.curve().min_area();
+        self.collect(point, &mut out);
+        out
+    }
+
+    fn collect(&self, point: usize, out: &mut Vec<(usize, (usize, usize))>) {
+        match self {
+            ShapeTree::Leaf { operand, curve, .. } => out.push((*operand, curve.points[point])),
+            ShapeTree::Cut {
+                left,
+                right,
+                choices,
+                ..
+            } => {
+                if let Choice::Cut(li, ri) = choices[point] {
+                    left.collect(li, out);
+                    right.collect(ri, out);
+                }
+            }
+        }
+    }
+}
+
+/// Default candidate set: each block as given, and rotated 90 degrees.
+pub fn rotations(rects: &[Rect]) -> Vec<Vec<(usize, usize)>> {
+    rects
+        .iter()
+        .map(|r| vec![(r.width(), r.height()), (r.height(), r.width())])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TreeItem;
+
+    #[test]
+    fn merge_vertical_sums_width_for_shared_height() {
+        let (left, _) = ShapeCurve::from_candidates_indexed(&[(2, 3)]);
+        let (right, _) = ShapeCurve::from_candidates_indexed(&[(4, 3)]);
+
+        let (curve, choices) = ShapeCurve::merge(&left, &right, Cut::Vertical);
+
+        assert_eq!(curve.points(), &[(6, 3)]);
+        assert!(matches!(choices[0], Choice::Cut(0, 0)));
+    }
+
+    #[test]
+    fn merge_horizontal_transposes_then_sums_heights() {
+        let (left, _) = ShapeCurve::from_candidates_indexed(&[(2, 3)]);
+        let (right, _) = ShapeCurve::from_candidates_indexed(&[(2, 5)]);
+
+        let (curve, _) = ShapeCurve::merge(&left, &right, Cut::Horizontal);
+
+        assert_eq!(curve.points(), &[(2, 8)]);
+    }
+
+    #[test]
+    fn build_picks_best_rotation_pair() {
+        // A single vertical cut over two rotatable blocks: 1x4/4x1 and
+        // 4x1/1x4. Rotating both to match (either both tall or both wide)
+        // beats any mismatched pairing.
+        let candidates = vec![vec![(1, 4), (4, 1)], vec![(4, 1), (1, 4)]];
+        let expr = vec![
+            TreeItem::Rect(0),
+            TreeItem::Rect(1),
+            TreeItem::Cut(Cut::Vertical),
+        ];
+        let npe = crate::NPE::new(expr);
+
+        let tree = ShapeTree::build(&npe, &candidates);
+        assert_eq!(tree.optimal_area(), 8.0);
+
+        let orientations = tree.orientations();
+        let by_operand: std::collections::HashMap<_, _> = orientations.into_iter().collect();
+        assert_eq!(
+            by_operand[&0], by_operand[&1],
+            "the optimal pairing keeps both blocks in the same orientation"
+        );
+    }
+}